@@ -16,7 +16,16 @@ use nostr_sdk::{
 use serde_json::json;
 use uuid::Uuid;
 
-use crate::structs::{InvoiceQueryParams, LnurlpCallback, LnurlpConfig, PluginState};
+use crate::{
+    routehints::route_hint_channels,
+    store::UserStore,
+    structs::{InvoiceQueryParams, LnurlpCallback, LnurlpConfig, PluginState, ZapRequestEntry},
+    tasks::save_zap_requests,
+};
+
+/// Cap on the serialized LUD-18 `payerdata`, so a sender can't inflate the
+/// invoice description (and the bolt11 it's embedded in) without bound.
+const MAX_PAYERDATA_LEN: usize = 512;
 
 pub async fn get_lnurlp_config(
     maybe_user: Option<axum::extract::Path<String>>,
@@ -24,7 +33,9 @@ pub async fn get_lnurlp_config(
 ) -> Result<Json<LnurlpConfig>, axum::response::Response> {
     if let Some(axum::extract::Path(user)) = maybe_user {
         let metadata = generate_user_metadata(&state, &user)
+            .await
             .map_err(|e| (StatusCode::NOT_FOUND, lnurl_error(&e.to_string())).into_response())?;
+        let comment_allowed = user_comment_allowed(&state, &user).await;
 
         Ok(Json(LnurlpConfig {
             callback: state
@@ -38,8 +49,13 @@ pub async fn get_lnurlp_config(
             min_sendable: state.min_sendable_msat,
             metadata: serde_json::to_string(&metadata).unwrap(),
             tag: "payRequest".to_owned(),
-            comment_allowed: None,
+            comment_allowed: (comment_allowed > 0).then_some(comment_allowed),
             allows_nostr: state.nostr_zapper_keys.is_some(),
+            // Zap receipts are always signed with the single global
+            // zapper key (see `tasks::payment_watcher`), so `nostrPubkey`
+            // must advertise that same key regardless of any `nostr_pubkey`
+            // the user has configured for their NIP-05 identity, or
+            // compliant clients will reject the receipt as wrongly signed.
             nostr_pubkey: state.nostr_zapper_keys.map(|p| p.public_key().to_hex()),
         }))
     } else {
@@ -49,11 +65,11 @@ pub async fn get_lnurlp_config(
             min_sendable: state.min_sendable_msat,
             metadata: serde_json::to_string(&vec![vec![
                 "text/plain".to_string(),
-                state.default_description,
+                state.default_description.clone(),
             ]])
             .unwrap(),
             tag: "payRequest".to_owned(),
-            comment_allowed: None,
+            comment_allowed: (state.comment_allowed > 0).then_some(state.comment_allowed),
             allows_nostr: state.nostr_zapper_keys.is_some(),
             nostr_pubkey: state.nostr_zapper_keys.map(|p| p.public_key().to_hex()),
         }))
@@ -72,6 +88,9 @@ pub async fn get_invoice(
     )
     .map_err(axum::response::IntoResponse::into_response)?;
 
+    let user_opt = maybe_user.as_ref().map(|p| p.0.clone());
+
+    let mut zap_relays = None;
     let description = match &params.nostr {
         Some(d) => {
             if state.nostr_zapper_keys.is_none() {
@@ -98,24 +117,80 @@ pub async fn get_invoice(
             verify_zap_request(&zap_request, params.amount).map_err(|e| {
                 (StatusCode::BAD_REQUEST, lnurl_error(&e.to_string())).into_response()
             })?;
+            zap_relays = zap_request
+                .tags
+                .iter()
+                .find(|t| t.kind() == TagKind::Relays)
+                .map(|t| t.as_slice().iter().skip(1).cloned().collect::<Vec<_>>());
             zap_request.as_json()
         }
         None => {
-            if let Some(user) = maybe_user {
-                serde_json::to_string(&generate_user_metadata(&state, &user).map_err(|e| {
+            if let Some(user) = &user_opt {
+                serde_json::to_string(&generate_user_metadata(&state, user).await.map_err(|e| {
                     (StatusCode::NOT_FOUND, lnurl_error(&e.to_string())).into_response()
                 })?)
                 .unwrap()
             } else {
                 serde_json::to_string(&vec![vec![
                     "text/plain".to_string(),
-                    state.default_description,
+                    state.default_description.clone(),
                 ]])
                 .unwrap()
             }
         }
     };
 
+    let zap_request_json = description.clone();
+
+    let comment_allowed = match &user_opt {
+        Some(user) => user_comment_allowed(&state, user).await,
+        None => state.comment_allowed,
+    };
+
+    let mut deschashonly = true;
+    let mut description = description;
+    if let Some(comment) = &params.comment {
+        if comment_allowed == 0 {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                lnurl_error("Comments are not accepted"),
+            )
+                .into_response());
+        }
+        if comment.chars().count() as u64 > comment_allowed {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                lnurl_error(&format!("`comment` longer than allowed: {comment_allowed}")),
+            )
+                .into_response());
+        }
+        let sanitized_comment = ammonia::clean(comment);
+        description = format!("{description}\ncomment: {sanitized_comment}");
+        deschashonly = false;
+    }
+    if let Some(payerdata) = &params.payerdata {
+        let payerdata_json: serde_json::Value = serde_json::from_str(payerdata).map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                lnurl_error(&format!("Invalid `payerdata`: {e}")),
+            )
+                .into_response()
+        })?;
+        let serialized_payerdata = payerdata_json.to_string();
+        if serialized_payerdata.len() > MAX_PAYERDATA_LEN {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                lnurl_error(&format!(
+                    "`payerdata` longer than allowed: {MAX_PAYERDATA_LEN}"
+                )),
+            )
+                .into_response());
+        }
+        let sanitized_payerdata = ammonia::clean(&serialized_payerdata);
+        description = format!("{description}\npayerData: {sanitized_payerdata}");
+        deschashonly = false;
+    }
+
     let mut cln_client = cln_rpc::ClnRpc::new(&state.rpc_path).await.map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -130,17 +205,60 @@ pub async fn get_invoice(
         AmountOrAny::Any
     };
 
+    let allowed_route_hint_channels = match &user_opt {
+        Some(user) => user_route_hint_channels(&state, user).await,
+        None => None,
+    };
+    let route_hints = if params.amount > 0 {
+        route_hint_channels(
+            &mut cln_client,
+            &state,
+            allowed_route_hint_channels.as_deref(),
+            params.amount,
+        )
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                lnurl_error(&e.to_string()),
+            )
+                .into_response()
+        })?
+    } else {
+        None
+    };
+
+    let label = make_invoice_label(user_opt.as_deref());
+    if let Some(relays) = zap_relays {
+        state.zap_requests.lock().insert(
+            label.clone(),
+            ZapRequestEntry {
+                zap_request: zap_request_json,
+                relays,
+            },
+        );
+        save_zap_requests(&state.plugin_dir, state.zap_requests.lock().clone())
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    lnurl_error(&e.to_string()),
+                )
+                    .into_response()
+            })?;
+    }
+
     let cln_response = cln_client
         .call_typed(&InvoiceRequest {
             amount_msat,
             description,
-            label: Uuid::new_v4().to_string(),
+            label,
             expiry: None,
             fallbacks: None,
             preimage: None,
-            exposeprivatechannels: None,
+            exposeprivatechannels: route_hints,
             cltv: None,
-            deschashonly: Some(true),
+            deschashonly: Some(deschashonly),
         })
         .await
         .map_err(|e| {
@@ -157,6 +275,43 @@ pub async fn get_invoice(
     }))
 }
 
+/// Builds an invoice label that also records which user the invoice was
+/// created for, so later stages (payment notifications, zap receipts) can
+/// recover the user from `waitanyinvoice`'s `label` field alone.
+pub(crate) fn make_invoice_label(user: Option<&str>) -> String {
+    match user {
+        Some(user) => format!("{user}:{}", Uuid::new_v4()),
+        None => Uuid::new_v4().to_string(),
+    }
+}
+
+/// Recovers the user encoded by [`make_invoice_label`], if any.
+pub(crate) fn label_user(label: &str) -> Option<&str> {
+    label.split_once(':').map(|(user, _)| user)
+}
+
+/// Whether `label` was generated by [`make_invoice_label`], as opposed to
+/// some other invoice settled on the node (or one of
+/// [`crate::registration`]'s, which use their own `register:`-prefixed
+/// labels) that this plugin had no part in creating.
+pub(crate) fn is_own_invoice_label(label: &str) -> bool {
+    let uuid_part = match label.split_once(':') {
+        Some((_, uuid)) => uuid,
+        None => label,
+    };
+    Uuid::parse_str(uuid_part).is_ok()
+}
+
+/// Recovers the LUD-12 comment [`get_invoice`] appended to the invoice
+/// description, if any.
+pub(crate) fn description_comment(description: &str) -> Option<String> {
+    let (_, rest) = description.split_once("\ncomment: ")?;
+    let comment = rest
+        .rsplit_once("\npayerData: ")
+        .map_or(rest, |(comment, _)| comment);
+    Some(comment.to_owned())
+}
+
 fn validate_invoice_amount(
     requested_amount: u64,
     min_sendable_msat: u64,
@@ -181,12 +336,59 @@ fn validate_invoice_amount(
     Ok(())
 }
 
-fn generate_user_metadata(
+async fn user_route_hint_channels(state: &PluginState, user: &str) -> Option<Vec<String>> {
+    state.users.lookup(user).await.ok()??.route_hint_channels
+}
+
+async fn user_comment_allowed(state: &PluginState, user: &str) -> u64 {
+    state
+        .users
+        .lookup(user)
+        .await
+        .ok()
+        .flatten()
+        .and_then(|m| m.comment_allowed)
+        .unwrap_or(state.comment_allowed)
+}
+
+#[derive(serde::Deserialize)]
+pub struct Nip05QueryParams {
+    pub name: String,
+}
+
+pub async fn get_nip05(
+    Query(params): Query<Nip05QueryParams>,
+    State(state): State<PluginState>,
+) -> Result<Json<serde_json::Value>, axum::response::Response> {
+    let metadata = state
+        .users
+        .lookup(&params.name)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                lnurl_error(&e.to_string()),
+            )
+                .into_response()
+        })?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, lnurl_error("User not found")).into_response())?;
+    let pubkey = metadata
+        .nostr_pubkey
+        .ok_or_else(|| (StatusCode::NOT_FOUND, lnurl_error("User not found")).into_response())?;
+
+    let mut response = json!({"names": {&params.name: pubkey}});
+    if let Some(relays) = metadata.nostr_relays {
+        response["relays"] = json!({params.name: relays});
+    }
+
+    Ok(Json(response))
+}
+
+async fn generate_user_metadata(
     state: &PluginState,
     user: &String,
 ) -> Result<Vec<Vec<String>>, anyhow::Error> {
-    let users = state.users.lock();
-    let Some(user_meta) = users.get(user) else {
+    let Some(user_meta) = state.users.lookup(user).await? else {
         return Err(anyhow!("User `{user}` not found!"));
     };
 
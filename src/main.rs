@@ -1,10 +1,12 @@
-use axum::{routing::get, Router};
+use axum::{http::Method, routing::get, Router};
 use bech32::{Bech32, Hrp};
 use cln_plugin::{
     options::{
         ConfigOption,
+        DefaultBooleanConfigOption,
         DefaultIntegerConfigOption,
         DefaultStringConfigOption,
+        IntegerConfigOption,
         StringConfigOption,
     },
     RpcMethodBuilder,
@@ -16,17 +18,27 @@ use tokio::{
     fs,
     io::{stdin, stdout},
 };
+use tower_http::cors::{AllowOrigin, CorsLayer};
 
 use crate::{
-    lnurl::{get_invoice, get_lnurlp_config},
+    lnurl::{get_invoice, get_lnurlp_config, get_nip05},
+    ratelimit::{evict_stale_buckets, rate_limit},
+    registration::{register_user, registration_status},
     rpc::user_list,
+    tls::{load_tls_config, reload_tls_config},
 };
 
 mod lnurl;
+mod notify;
 mod parse;
+mod ratelimit;
+mod registration;
+mod routehints;
 mod rpc;
+mod store;
 mod structs;
 mod tasks;
+mod tls;
 
 const OPT_CLNADDRESS_MIN_RECEIVABLE: DefaultIntegerConfigOption =
     ConfigOption::new_i64_with_default(
@@ -58,8 +70,110 @@ const OPT_CLNADDRESS_NOSTR_PRIVKEY: StringConfigOption = ConfigOption::new_str_n
     "clnaddress-nostr-privkey",
     "Nostr private key for zap receipts",
 );
+const OPT_CLNADDRESS_CORS_ORIGINS: DefaultStringConfigOption = ConfigOption::new_str_with_default(
+    "clnaddress-cors-origins",
+    "*",
+    "Comma-separated list of origins allowed to call the LNURL endpoints, or `*` for any origin",
+);
+const OPT_CLNADDRESS_COMMENT_ALLOWED: DefaultIntegerConfigOption =
+    ConfigOption::new_i64_with_default(
+        "clnaddress-comment-allowed",
+        0,
+        "Maximum length of a LUD-12 comment accepted with an invoice request, 0 disables comments",
+    );
+const OPT_CLNADDRESS_USER_BACKEND: DefaultStringConfigOption = ConfigOption::new_str_with_default(
+    "clnaddress-user-backend",
+    "json",
+    "Where user addresses are looked up: `json` (default), `ldap` or `sqlite`",
+);
+const OPT_CLNADDRESS_LDAP_URL: StringConfigOption = ConfigOption::new_str_no_default(
+    "clnaddress-ldap-url",
+    "LDAP server URL, e.g. ldap://localhost:389",
+);
+const OPT_CLNADDRESS_LDAP_BIND_DN: StringConfigOption =
+    ConfigOption::new_str_no_default("clnaddress-ldap-bind-dn", "Bind DN for the LDAP connection");
+const OPT_CLNADDRESS_LDAP_BIND_PASSWORD: StringConfigOption = ConfigOption::new_str_no_default(
+    "clnaddress-ldap-bind-password",
+    "Bind password for the LDAP connection",
+);
+const OPT_CLNADDRESS_LDAP_SEARCH_BASE: StringConfigOption = ConfigOption::new_str_no_default(
+    "clnaddress-ldap-search-base",
+    "Search base DN users are looked up under",
+);
+const OPT_CLNADDRESS_LDAP_SEARCH_FILTER: StringConfigOption = ConfigOption::new_str_no_default(
+    "clnaddress-ldap-search-filter",
+    "LDAP search filter, `{user}` is replaced with the requested address local-part",
+);
+const OPT_CLNADDRESS_LDAP_ATTR_DESCRIPTION: StringConfigOption = ConfigOption::new_str_no_default(
+    "clnaddress-ldap-attr-description",
+    "LDAP attribute mapped to the user's description",
+);
+const OPT_CLNADDRESS_LDAP_ATTR_IS_EMAIL: StringConfigOption = ConfigOption::new_str_no_default(
+    "clnaddress-ldap-attr-is-email",
+    "LDAP attribute mapped to the user's `is_email` flag",
+);
+const OPT_CLNADDRESS_WEBHOOK_URL: StringConfigOption = ConfigOption::new_str_no_default(
+    "clnaddress-webhook-url",
+    "URL POSTed with a JSON payload whenever an invoice gets paid",
+);
+const OPT_CLNADDRESS_EXEC_COMMAND: StringConfigOption = ConfigOption::new_str_no_default(
+    "clnaddress-exec-command",
+    "Shell command run whenever an invoice gets paid, fields passed as CLNADDRESS_* env vars",
+);
+const OPT_CLNADDRESS_TLS_CERT: StringConfigOption = ConfigOption::new_str_no_default(
+    "clnaddress-tls-cert",
+    "Path to a PEM TLS certificate. Set together with clnaddress-tls-key to serve HTTPS directly",
+);
+const OPT_CLNADDRESS_TLS_KEY: StringConfigOption = ConfigOption::new_str_no_default(
+    "clnaddress-tls-key",
+    "Path to the PEM private key matching clnaddress-tls-cert",
+);
+const OPT_CLNADDRESS_RATE_LIMIT: IntegerConfigOption = ConfigOption::new_i64_no_default(
+    "clnaddress-rate-limit",
+    "Maximum requests per clnaddress-rate-window-secs per client, unset disables rate limiting",
+);
+const OPT_CLNADDRESS_RATE_WINDOW_SECS: DefaultIntegerConfigOption =
+    ConfigOption::new_i64_with_default(
+        "clnaddress-rate-window-secs",
+        60,
+        "Length in seconds of the rate limit window",
+    );
+const OPT_CLNADDRESS_RATE_TRUST_FORWARDED_FOR: DefaultBooleanConfigOption =
+    ConfigOption::new_bool_with_default(
+        "clnaddress-rate-trust-forwarded-for",
+        false,
+        "Key rate limiting on the first address in a client-supplied X-Forwarded-For header \
+         instead of the socket's peer address. Only enable behind a trusted reverse proxy",
+    );
+const OPT_CLNADDRESS_REGISTRATION_ENABLE: DefaultBooleanConfigOption =
+    ConfigOption::new_bool_with_default(
+        "clnaddress-registration-enable",
+        false,
+        "Allow anyone to self-register a new LN address via /register/{user} against payment",
+    );
+const OPT_CLNADDRESS_REGISTRATION_PRICE_MSAT: IntegerConfigOption =
+    ConfigOption::new_i64_no_default(
+        "clnaddress-registration-price-msat",
+        "Price in msat of a self-service registration invoice, required if \
+         clnaddress-registration-enable is true",
+    );
+const OPT_CLNADDRESS_REGISTRATION_EXPIRY_SECS: DefaultIntegerConfigOption =
+    ConfigOption::new_i64_with_default(
+        "clnaddress-registration-expiry-secs",
+        900,
+        "How long an unpaid registration invoice reserves its name before becoming available again",
+    );
+const OPT_CLNADDRESS_EXPOSE_PRIVATE_CHANNELS: DefaultBooleanConfigOption =
+    ConfigOption::new_bool_with_default(
+        "clnaddress-expose-private-channels",
+        false,
+        "Hint eligible private channels with enough inbound capacity in generated invoices, \
+         improving receive reliability for nodes without public liquidity",
+    );
 const CLNADDRESS_USERS_FILENAME: &str = "users.json";
 const CLNADDRESS_PAYINDEX_FILENAME: &str = "payindex.json";
+const CLNADDRESS_ZAPMAP_FILENAME: &str = "zapmap.json";
+const CLNADDRESS_REGISTRATIONS_FILENAME: &str = "registrations.json";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -74,10 +188,34 @@ async fn main() -> anyhow::Result<()> {
         .option(OPT_CLNADDRESS_MAX_RECEIVABLE)
         .option(OPT_CLNADDRESS_DESCRIPTION)
         .option(OPT_CLNADDRESS_NOSTR_PRIVKEY)
+        .option(OPT_CLNADDRESS_CORS_ORIGINS)
+        .option(OPT_CLNADDRESS_COMMENT_ALLOWED)
+        .option(OPT_CLNADDRESS_USER_BACKEND)
+        .option(OPT_CLNADDRESS_LDAP_URL)
+        .option(OPT_CLNADDRESS_LDAP_BIND_DN)
+        .option(OPT_CLNADDRESS_LDAP_BIND_PASSWORD)
+        .option(OPT_CLNADDRESS_LDAP_SEARCH_BASE)
+        .option(OPT_CLNADDRESS_LDAP_SEARCH_FILTER)
+        .option(OPT_CLNADDRESS_LDAP_ATTR_DESCRIPTION)
+        .option(OPT_CLNADDRESS_LDAP_ATTR_IS_EMAIL)
+        .option(OPT_CLNADDRESS_WEBHOOK_URL)
+        .option(OPT_CLNADDRESS_EXEC_COMMAND)
+        .option(OPT_CLNADDRESS_TLS_CERT)
+        .option(OPT_CLNADDRESS_TLS_KEY)
+        .option(OPT_CLNADDRESS_RATE_LIMIT)
+        .option(OPT_CLNADDRESS_RATE_WINDOW_SECS)
+        .option(OPT_CLNADDRESS_RATE_TRUST_FORWARDED_FOR)
+        .option(OPT_CLNADDRESS_REGISTRATION_ENABLE)
+        .option(OPT_CLNADDRESS_REGISTRATION_PRICE_MSAT)
+        .option(OPT_CLNADDRESS_REGISTRATION_EXPIRY_SECS)
+        .option(OPT_CLNADDRESS_EXPOSE_PRIVATE_CHANNELS)
         .rpcmethod_from_builder(
             RpcMethodBuilder::new("clnaddress-adduser", user_add)
                 .description("Add a user with optional metadata to create a ln address")
-                .usage("user [is_email] [description]"),
+                .usage(
+                    "user [is_email] [description] [nostr_pubkey] [comment_allowed] \
+                     [nostr_relays] [route_hint_channels]",
+                ),
         )
         .rpcmethod_from_builder(
             RpcMethodBuilder::new("clnaddress-deluser", user_del)
@@ -109,11 +247,31 @@ async fn main() -> anyhow::Result<()> {
 
     read_plugin_config_files(&mut state).await?;
 
+    let cors = CorsLayer::new()
+        .allow_methods([Method::GET, Method::OPTIONS])
+        .allow_headers(tower_http::cors::Any)
+        .allow_origin(
+            state
+                .cors_allowed_origins
+                .clone()
+                .map_or(AllowOrigin::any(), |origins| {
+                    AllowOrigin::list(origins.iter().filter_map(|o| o.parse().ok()))
+                }),
+        );
+
     let lnaddress_router = Router::new()
         .route("/lnurlp", get(get_lnurlp_config))
         .route("/.well-known/lnurlp/{user}", get(get_lnurlp_config))
         .route("/invoice", get(get_invoice))
         .route("/invoice/{user}", get(get_invoice))
+        .route("/.well-known/nostr.json", get(get_nip05))
+        .route("/register/{user}", get(register_user))
+        .route("/register/{user}/confirm", get(registration_status))
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit,
+        ))
+        .layer(cors)
         .with_state(state.clone());
 
     let listener = match tokio::net::TcpListener::bind(&state.listen_address).await {
@@ -125,12 +283,25 @@ async fn main() -> anyhow::Result<()> {
         }
     };
 
+    let tls_config = match (&state.tls_cert, &state.tls_key) {
+        (Some(cert), Some(key)) => match load_tls_config(cert, key).await {
+            Ok(c) => Some(c),
+            Err(e) => {
+                return configured_plugin
+                    .disable(&format!("Error loading TLS cert/key: {e}"))
+                    .await
+            }
+        },
+        _ => None,
+    };
+
     let plugin = configured_plugin.start(state.clone()).await?;
 
     log::info!(
-        "Starting lnurlp server. LISTEN:{} BASE_ADDRESS:{}",
+        "Starting lnurlp server. LISTEN:{} BASE_ADDRESS:{} TLS:{}",
         state.listen_address,
-        state.base_url
+        state.base_url,
+        tls_config.is_some()
     );
     log::info!(
         "LNURL: {}",
@@ -139,9 +310,17 @@ async fn main() -> anyhow::Result<()> {
             state.base_url.join("lnurlp")?.to_string().as_bytes()
         )?
     );
+    if let Some(tls_config) = tls_config.clone() {
+        let cert = state.tls_cert.clone().unwrap();
+        let key = state.tls_key.clone().unwrap();
+        tokio::spawn(reload_tls_config(tls_config, cert, key));
+    }
+    if state.rate_limit.is_some() {
+        tokio::spawn(evict_stale_buckets(state.clone()));
+    }
     let plugin_clone = plugin.clone();
     tokio::spawn(async move {
-        match axum::serve(listener, lnaddress_router.into_make_service()).await {
+        match serve_lnurl_router(listener, lnaddress_router, tls_config).await {
             Ok(()) => _ = plugin_clone.shutdown(),
             Err(e) => {
                 log_error(&format!("Error running server: {e}"));
@@ -149,13 +328,17 @@ async fn main() -> anyhow::Result<()> {
             }
         }
     });
-    if plugin.state().nostr_zapper_keys.is_some() {
+    if plugin.state().nostr_zapper_keys.is_some()
+        || plugin.state().webhook_url.is_some()
+        || plugin.state().exec_command.is_some()
+        || plugin.state().registration_enabled
+    {
         let plugin_zap_clone = plugin.clone();
         tokio::spawn(async move {
-            match tasks::zap_receipt_sender(plugin_zap_clone.clone()).await {
+            match tasks::payment_watcher(plugin_zap_clone.clone()).await {
                 Ok(()) => _ = plugin_zap_clone.shutdown(),
                 Err(e) => {
-                    log_error(&format!("Error running zap_receipt_sender: {e}"));
+                    log_error(&format!("Error running payment_watcher: {e}"));
                     _ = plugin_zap_clone.shutdown();
                 }
             }
@@ -165,6 +348,27 @@ async fn main() -> anyhow::Result<()> {
     plugin.join().await
 }
 
+/// Serves `router` over HTTPS on `listener` when `tls_config` is set, or
+/// plain HTTP otherwise.
+async fn serve_lnurl_router(
+    listener: tokio::net::TcpListener,
+    router: Router,
+    tls_config: Option<axum_server::tls_rustls::RustlsConfig>,
+) -> Result<(), anyhow::Error> {
+    let make_service = router.into_make_service_with_connect_info::<std::net::SocketAddr>();
+    match tls_config {
+        Some(tls_config) => {
+            axum_server::from_tcp_rustls(listener.into_std()?, tls_config)
+                .serve(make_service)
+                .await?;
+        }
+        None => {
+            axum::serve(listener, make_service).await?;
+        }
+    }
+    Ok(())
+}
+
 fn log_error(error: &str) {
     println!(
         "{}",
@@ -182,18 +386,26 @@ async fn read_plugin_config_files(state: &mut PluginState) -> Result<(), anyhow:
             _ => log::warn!("Error creating directory: {e}"),
         },
     }
-    match fs::read_to_string(state.plugin_dir.join(CLNADDRESS_USERS_FILENAME)).await {
-        Ok(content) => *state.users.lock() = serde_json::from_str(&content)?,
+    state.users.init().await?;
+    match fs::read_to_string(state.plugin_dir.join(CLNADDRESS_PAYINDEX_FILENAME)).await {
+        Ok(content) => state.payindex = serde_json::from_str(&content)?,
         Err(e) => match e.kind() {
             std::io::ErrorKind::NotFound => (),
-            _ => log::warn!("Could not read {CLNADDRESS_USERS_FILENAME} file: {e}"),
+            _ => log::warn!("Could not read {CLNADDRESS_PAYINDEX_FILENAME} file: {e}"),
         },
     }
-    match fs::read_to_string(state.plugin_dir.join(CLNADDRESS_PAYINDEX_FILENAME)).await {
-        Ok(content) => state.payindex = serde_json::from_str(&content)?,
+    match fs::read_to_string(state.plugin_dir.join(CLNADDRESS_ZAPMAP_FILENAME)).await {
+        Ok(content) => *state.zap_requests.lock() = serde_json::from_str(&content)?,
         Err(e) => match e.kind() {
             std::io::ErrorKind::NotFound => (),
-            _ => log::warn!("Could not read {CLNADDRESS_PAYINDEX_FILENAME} file: {e}"),
+            _ => log::warn!("Could not read {CLNADDRESS_ZAPMAP_FILENAME} file: {e}"),
+        },
+    }
+    match fs::read_to_string(state.plugin_dir.join(CLNADDRESS_REGISTRATIONS_FILENAME)).await {
+        Ok(content) => *state.pending_registrations.lock() = serde_json::from_str(&content)?,
+        Err(e) => match e.kind() {
+            std::io::ErrorKind::NotFound => (),
+            _ => log::warn!("Could not read {CLNADDRESS_REGISTRATIONS_FILENAME} file: {e}"),
         },
     }
     Ok(())
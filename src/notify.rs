@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::structs::PluginState;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+const WEBHOOK_RETRIES: u32 = 3;
+const EXEC_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Fields reported to the configured webhook/exec targets when an invoice
+/// this plugin created gets paid.
+#[derive(Debug, Clone, Serialize)]
+pub struct PaymentNotification {
+    pub user: Option<String>,
+    pub label: String,
+    pub amount_msat: u64,
+    pub payment_hash: String,
+    pub preimage: Option<String>,
+    /// LUD-12 comment supplied with the invoice request, if any.
+    pub comment: Option<String>,
+    pub paid_at: Option<u64>,
+    pub bolt11: Option<String>,
+}
+
+/// Fires the configured webhook and/or exec command for a settled invoice.
+/// Meant to be spawned as its own task so a slow endpoint can't stall the
+/// `waitanyinvoice` cursor in [`crate::tasks::payment_watcher`].
+pub async fn dispatch(state: PluginState, notification: PaymentNotification) {
+    if let Some(url) = &state.webhook_url {
+        if let Err(e) = send_webhook(url, &notification).await {
+            log::warn!("Could not deliver payment webhook: {e}");
+        }
+    }
+    if let Some(command) = &state.exec_command {
+        if let Err(e) = run_exec(command, &notification).await {
+            log::warn!("Could not run payment exec command: {e}");
+        }
+    }
+}
+
+async fn send_webhook(url: &str, notification: &PaymentNotification) -> Result<(), anyhow::Error> {
+    let client = reqwest::Client::new();
+    let mut last_err = None;
+    for attempt in 1..=WEBHOOK_RETRIES {
+        match client
+            .post(url)
+            .timeout(WEBHOOK_TIMEOUT)
+            .json(notification)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+        {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                log::debug!("Webhook attempt {attempt} failed: {e}");
+                last_err = Some(e);
+            }
+        }
+    }
+    Err(anyhow::anyhow!(
+        "Webhook gave up after {WEBHOOK_RETRIES} attempts: {}",
+        last_err.unwrap()
+    ))
+}
+
+async fn run_exec(command: &str, notification: &PaymentNotification) -> Result<(), anyhow::Error> {
+    let status = tokio::time::timeout(
+        EXEC_TIMEOUT,
+        tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env(
+                "CLNADDRESS_USER",
+                notification.user.clone().unwrap_or_default(),
+            )
+            .env("CLNADDRESS_LABEL", &notification.label)
+            .env(
+                "CLNADDRESS_AMOUNT_MSAT",
+                notification.amount_msat.to_string(),
+            )
+            .env("CLNADDRESS_PAYMENT_HASH", &notification.payment_hash)
+            .env(
+                "CLNADDRESS_PREIMAGE",
+                notification.preimage.clone().unwrap_or_default(),
+            )
+            .env(
+                "CLNADDRESS_COMMENT",
+                notification.comment.clone().unwrap_or_default(),
+            )
+            .env(
+                "CLNADDRESS_PAID_AT",
+                notification
+                    .paid_at
+                    .map(|t| t.to_string())
+                    .unwrap_or_default(),
+            )
+            .env(
+                "CLNADDRESS_BOLT11",
+                notification.bolt11.clone().unwrap_or_default(),
+            )
+            .status(),
+    )
+    .await??;
+    if !status.success() {
+        return Err(anyhow::anyhow!("exec command exited with {status}"));
+    }
+    Ok(())
+}
@@ -3,6 +3,7 @@ use std::{
     net::{SocketAddr, ToSocketAddrs},
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 use anyhow::anyhow;
@@ -11,14 +12,42 @@ use parking_lot::Mutex;
 use url::Url;
 
 use crate::{
+    store::{JsonUserStore, UserStore},
     PluginState,
     OPT_CLNADDRESS_BASE_URL,
+    OPT_CLNADDRESS_COMMENT_ALLOWED,
+    OPT_CLNADDRESS_CORS_ORIGINS,
     OPT_CLNADDRESS_DESCRIPTION,
+    OPT_CLNADDRESS_EXEC_COMMAND,
+    OPT_CLNADDRESS_EXPOSE_PRIVATE_CHANNELS,
     OPT_CLNADDRESS_LISTEN,
     OPT_CLNADDRESS_MAX_RECEIVABLE,
     OPT_CLNADDRESS_MIN_RECEIVABLE,
     OPT_CLNADDRESS_NOSTR_PRIVKEY,
+    OPT_CLNADDRESS_RATE_LIMIT,
+    OPT_CLNADDRESS_RATE_TRUST_FORWARDED_FOR,
+    OPT_CLNADDRESS_RATE_WINDOW_SECS,
+    OPT_CLNADDRESS_REGISTRATION_ENABLE,
+    OPT_CLNADDRESS_REGISTRATION_EXPIRY_SECS,
+    OPT_CLNADDRESS_REGISTRATION_PRICE_MSAT,
+    OPT_CLNADDRESS_TLS_CERT,
+    OPT_CLNADDRESS_TLS_KEY,
+    OPT_CLNADDRESS_USER_BACKEND,
+    OPT_CLNADDRESS_WEBHOOK_URL,
 };
+#[cfg(feature = "ldap")]
+use crate::{
+    store::{LdapConfig, LdapUserStore},
+    OPT_CLNADDRESS_LDAP_ATTR_DESCRIPTION,
+    OPT_CLNADDRESS_LDAP_ATTR_IS_EMAIL,
+    OPT_CLNADDRESS_LDAP_BIND_DN,
+    OPT_CLNADDRESS_LDAP_BIND_PASSWORD,
+    OPT_CLNADDRESS_LDAP_SEARCH_BASE,
+    OPT_CLNADDRESS_LDAP_SEARCH_FILTER,
+    OPT_CLNADDRESS_LDAP_URL,
+};
+#[cfg(feature = "sqlite")]
+use crate::store::SqliteUserStore;
 
 pub fn get_startup_options(
     plugin: &ConfiguredPlugin<PluginState, tokio::io::Stdin, tokio::io::Stdout>,
@@ -89,16 +118,134 @@ pub fn get_startup_options(
 
     let plugin_dir = Path::new(&plugin.configuration().lightning_dir).join("clnaddress");
 
+    let cors_origins_opt = plugin.option(&OPT_CLNADDRESS_CORS_ORIGINS)?;
+    let cors_allowed_origins = if cors_origins_opt.trim() == "*" {
+        None
+    } else {
+        Some(
+            cors_origins_opt
+                .split(',')
+                .map(|o| o.trim().to_owned())
+                .collect(),
+        )
+    };
+
+    let comment_allowed = u64::try_from(plugin.option(&OPT_CLNADDRESS_COMMENT_ALLOWED)?)?;
+
+    let webhook_url = plugin.option(&OPT_CLNADDRESS_WEBHOOK_URL)?;
+    let exec_command = plugin.option(&OPT_CLNADDRESS_EXEC_COMMAND)?;
+
+    let tls_cert = plugin.option(&OPT_CLNADDRESS_TLS_CERT)?.map(PathBuf::from);
+    let tls_key = plugin.option(&OPT_CLNADDRESS_TLS_KEY)?.map(PathBuf::from);
+    if tls_cert.is_some() != tls_key.is_some() {
+        return Err(anyhow!(
+            "`{}` and `{}` must be set together",
+            OPT_CLNADDRESS_TLS_CERT.name(),
+            OPT_CLNADDRESS_TLS_KEY.name()
+        ));
+    }
+
+    let rate_limit = plugin
+        .option(&OPT_CLNADDRESS_RATE_LIMIT)?
+        .map(u64::try_from)
+        .transpose()?;
+    let rate_window = Duration::from_secs(u64::try_from(
+        plugin.option(&OPT_CLNADDRESS_RATE_WINDOW_SECS)?,
+    )?);
+    let rate_trust_forwarded_for = plugin.option(&OPT_CLNADDRESS_RATE_TRUST_FORWARDED_FOR)?;
+
+    let registration_enabled = plugin.option(&OPT_CLNADDRESS_REGISTRATION_ENABLE)?;
+    let registration_price_msat = plugin.option(&OPT_CLNADDRESS_REGISTRATION_PRICE_MSAT)?;
+    if registration_enabled && registration_price_msat.is_none() {
+        return Err(anyhow!(
+            "`{}` is required when `{}` is enabled",
+            OPT_CLNADDRESS_REGISTRATION_PRICE_MSAT.name(),
+            OPT_CLNADDRESS_REGISTRATION_ENABLE.name()
+        ));
+    }
+    let registration_price_msat = registration_price_msat.map(u64::try_from).transpose()?;
+    let registration_expiry = Duration::from_secs(u64::try_from(
+        plugin.option(&OPT_CLNADDRESS_REGISTRATION_EXPIRY_SECS)?,
+    )?);
+
+    let expose_private_channels = plugin.option(&OPT_CLNADDRESS_EXPOSE_PRIVATE_CHANNELS)?;
+
+    let users: Arc<dyn UserStore> = match plugin.option(&OPT_CLNADDRESS_USER_BACKEND)?.as_str() {
+        "json" => Arc::new(JsonUserStore::new(plugin_dir.clone())),
+        #[cfg(feature = "ldap")]
+        "ldap" => Arc::new(LdapUserStore::new(LdapConfig {
+            url: plugin.option(&OPT_CLNADDRESS_LDAP_URL)?.ok_or_else(|| {
+                anyhow!(
+                    "`{}` is required for the ldap backend",
+                    OPT_CLNADDRESS_LDAP_URL.name()
+                )
+            })?,
+            bind_dn: plugin
+                .option(&OPT_CLNADDRESS_LDAP_BIND_DN)?
+                .ok_or_else(|| {
+                    anyhow!(
+                        "`{}` is required for the ldap backend",
+                        OPT_CLNADDRESS_LDAP_BIND_DN.name()
+                    )
+                })?,
+            bind_password: plugin
+                .option(&OPT_CLNADDRESS_LDAP_BIND_PASSWORD)?
+                .ok_or_else(|| {
+                    anyhow!(
+                        "`{}` is required for the ldap backend",
+                        OPT_CLNADDRESS_LDAP_BIND_PASSWORD.name()
+                    )
+                })?,
+            search_base: plugin
+                .option(&OPT_CLNADDRESS_LDAP_SEARCH_BASE)?
+                .ok_or_else(|| {
+                    anyhow!(
+                        "`{}` is required for the ldap backend",
+                        OPT_CLNADDRESS_LDAP_SEARCH_BASE.name()
+                    )
+                })?,
+            search_filter: plugin
+                .option(&OPT_CLNADDRESS_LDAP_SEARCH_FILTER)?
+                .ok_or_else(|| {
+                    anyhow!(
+                        "`{}` is required for the ldap backend",
+                        OPT_CLNADDRESS_LDAP_SEARCH_FILTER.name()
+                    )
+                })?,
+            attr_description: plugin.option(&OPT_CLNADDRESS_LDAP_ATTR_DESCRIPTION)?,
+            attr_is_email: plugin.option(&OPT_CLNADDRESS_LDAP_ATTR_IS_EMAIL)?,
+        })),
+        #[cfg(feature = "sqlite")]
+        "sqlite" => Arc::new(SqliteUserStore::new(plugin_dir.clone())?),
+        other => return Err(anyhow!("`{}` is not a known user backend", other)),
+    };
+
     Ok(PluginState {
         rpc_path,
         max_sendable_msat,
         min_sendable_msat,
         default_description,
-        users: Arc::new(Mutex::new(HashMap::new())),
+        users,
         plugin_dir,
         base_url,
         nostr_zapper_keys,
         payindex: 0,
         listen_address,
+        zap_requests: Arc::new(Mutex::new(HashMap::new())),
+        cors_allowed_origins,
+        comment_allowed,
+        webhook_url,
+        exec_command,
+        tls_cert,
+        tls_key,
+        rate_limit,
+        rate_window,
+        rate_trust_forwarded_for,
+        rate_buckets: Arc::new(Mutex::new(HashMap::new())),
+        registration_enabled,
+        registration_price_msat,
+        registration_expiry,
+        pending_registrations: Arc::new(Mutex::new(HashMap::new())),
+        expose_private_channels,
     })
 }
@@ -0,0 +1,122 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::structs::PluginState;
+
+/// How long an idle bucket is kept around after its window expires before
+/// [`evict_stale_buckets`] drops it.
+const BUCKET_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+const EVICTION_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Axum middleware enforcing `clnaddress-rate-limit` requests per
+/// `clnaddress-rate-window-secs` per client, keyed on IP and, for the LNURL
+/// routes carrying a `{user}` path segment, on the user as well. A no-op when
+/// `rate_limit` is unset.
+pub async fn rate_limit(
+    State(state): State<PluginState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(limit) = state.rate_limit else {
+        return next.run(req).await;
+    };
+
+    let ip = client_ip(&state, &headers, addr);
+    let key = match path_user(req.uri().path()) {
+        Some(user) => format!("{ip}:{user}"),
+        None => ip.to_string(),
+    };
+
+    let now = Instant::now();
+    let retry_after = {
+        let mut buckets = state.rate_buckets.lock();
+        let bucket = buckets.entry(key).or_insert(RateBucket {
+            count: 0,
+            window_start: now,
+        });
+        if now.duration_since(bucket.window_start) >= state.rate_window {
+            bucket.count = 0;
+            bucket.window_start = now;
+        }
+        bucket.count += 1;
+        (bucket.count > limit)
+            .then(|| state.rate_window.saturating_sub(now.duration_since(bucket.window_start)))
+    };
+
+    let Some(retry_after) = retry_after else {
+        return next.run(req).await;
+    };
+
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [("Retry-After", retry_after.as_secs().max(1).to_string())],
+        "rate limit exceeded",
+    )
+        .into_response()
+}
+
+/// Periodically drops buckets that haven't seen a request in
+/// [`BUCKET_IDLE_TIMEOUT`], so long-running plugins don't grow the map
+/// unbounded as new clients come and go. Meant to be spawned as its own
+/// task alongside the server.
+pub async fn evict_stale_buckets(state: PluginState) {
+    let mut interval = tokio::time::interval(EVICTION_INTERVAL);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let now = Instant::now();
+        state
+            .rate_buckets
+            .lock()
+            .retain(|_, bucket| {
+                now.duration_since(bucket.window_start)
+                    < state.rate_window.saturating_add(BUCKET_IDLE_TIMEOUT)
+            });
+    }
+}
+
+/// A per-client fixed-window counter. Resets to zero once `window_start` is
+/// older than `PluginState::rate_window`; requests from the previous window
+/// are not carried over into the next one.
+#[derive(Debug)]
+pub struct RateBucket {
+    count: u64,
+    window_start: Instant,
+}
+
+/// The two LNURL routes that carry a `{user}` path segment, so the bucket key
+/// can include it; every other route, including `/register/{user}`, is keyed
+/// on IP alone.
+fn path_user(path: &str) -> Option<&str> {
+    path.strip_prefix("/invoice/")
+        .or_else(|| path.strip_prefix("/.well-known/lnurlp/"))
+}
+
+fn client_ip(state: &PluginState, headers: &HeaderMap, addr: SocketAddr) -> IpAddr {
+    if state.rate_trust_forwarded_for {
+        if let Some(forwarded) = headers
+            .get("X-Forwarded-For")
+            .and_then(|v| v.to_str().ok())
+        {
+            if let Some(ip) = forwarded
+                .split(',')
+                .next()
+                .and_then(|s| s.trim().parse::<IpAddr>().ok())
+            {
+                return ip;
+            }
+        }
+    }
+    addr.ip()
+}
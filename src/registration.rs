@@ -0,0 +1,274 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use cln_rpc::{
+    model::requests::InvoiceRequest,
+    primitives::{Amount, AmountOrAny},
+};
+use serde::Serialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    store::UserStore,
+    structs::{PendingRegistration, PluginState, UserMetadata},
+    tasks::save_pending_registrations,
+};
+
+/// Prefix on invoice labels that marks them as self-service registration
+/// invoices, so [`crate::tasks::payment_watcher`] can tell them apart from
+/// regular payment/zap invoices without a second `waitanyinvoice` cursor.
+const LABEL_PREFIX: &str = "register:";
+
+fn make_registration_label(user: &str) -> String {
+    format!("{LABEL_PREFIX}{user}:{}", Uuid::new_v4())
+}
+
+/// Recovers the user encoded by [`make_registration_label`], if `label`
+/// carries the registration prefix at all.
+pub(crate) fn registration_label_user(label: &str) -> Option<&str> {
+    label
+        .strip_prefix(LABEL_PREFIX)?
+        .rsplit_once(':')
+        .map(|(user, _)| user)
+}
+
+#[derive(Debug, Serialize)]
+struct RegisterResponse {
+    pr: String,
+    #[serde(rename = "expiresAt")]
+    expires_at: u64,
+}
+
+/// `GET /register/{user}`. Issues an invoice for `clnaddress-registration-
+/// price-msat`; paying it promotes `user` into the live user store once
+/// [`crate::tasks::payment_watcher`] sees it settle. Re-requesting while a
+/// prior invoice for the same user is still unexpired returns that same
+/// invoice instead of minting a new one.
+pub async fn register_user(
+    Path(user): Path<String>,
+    State(state): State<PluginState>,
+) -> Result<Json<RegisterResponse>, axum::response::Response> {
+    let Some(price_msat) = registration_price(&state)? else {
+        return Err((StatusCode::NOT_FOUND, registration_error("Registration is not enabled"))
+            .into_response());
+    };
+
+    if !valid_user_name(&user) {
+        return Err(
+            (StatusCode::BAD_REQUEST, registration_error("Invalid user name")).into_response(),
+        );
+    }
+
+    if state
+        .users
+        .lookup(&user)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                registration_error(&e.to_string()),
+            )
+                .into_response()
+        })?
+        .is_some()
+    {
+        return Err(
+            (StatusCode::CONFLICT, registration_error("User already exists")).into_response(),
+        );
+    }
+
+    let now = now_unix();
+    let expires_at = now + state.registration_expiry.as_secs();
+    let label = make_registration_label(&user);
+    // Reserve the name under lock before the RPC round-trip below, so two
+    // concurrent requests for the same `user` can't both observe "nothing
+    // pending" and each mint a payable invoice. Whichever request loses the
+    // race sees this placeholder (identified by its still-empty `bolt11`)
+    // and is turned away instead of overwriting it once the winner's
+    // `insert` below fills it in.
+    {
+        let mut pending_registrations = state.pending_registrations.lock();
+        match pending_registrations.get(&user) {
+            Some(pending) if pending.expires_at > now && !pending.bolt11.is_empty() => {
+                return Ok(Json(RegisterResponse {
+                    pr: pending.bolt11.clone(),
+                    expires_at: pending.expires_at,
+                }));
+            }
+            Some(pending) if pending.expires_at > now => {
+                return Err((
+                    StatusCode::CONFLICT,
+                    registration_error(
+                        "A registration invoice for this name is already being created",
+                    ),
+                )
+                    .into_response());
+            }
+            _ => {
+                pending_registrations.insert(
+                    user.clone(),
+                    PendingRegistration {
+                        metadata: UserMetadata {
+                            is_email: None,
+                            description: None,
+                            nostr_pubkey: None,
+                            comment_allowed: None,
+                            nostr_relays: None,
+                            route_hint_channels: None,
+                        },
+                        label: label.clone(),
+                        bolt11: String::new(),
+                        expires_at,
+                    },
+                );
+            }
+        }
+    }
+
+    let mut cln_client = cln_rpc::ClnRpc::new(&state.rpc_path).await.map_err(|e| {
+        state.pending_registrations.lock().remove(&user);
+        (StatusCode::INTERNAL_SERVER_ERROR, registration_error(&e.to_string())).into_response()
+    })?;
+
+    let cln_response = cln_client
+        .call_typed(&InvoiceRequest {
+            amount_msat: AmountOrAny::Amount(Amount::from_msat(price_msat)),
+            description: format!("Register LN address {user}"),
+            label: label.clone(),
+            expiry: Some(state.registration_expiry.as_secs()),
+            fallbacks: None,
+            preimage: None,
+            exposeprivatechannels: None,
+            cltv: None,
+            deschashonly: Some(true),
+        })
+        .await
+        .map_err(|e| {
+            state.pending_registrations.lock().remove(&user);
+            (StatusCode::INTERNAL_SERVER_ERROR, registration_error(&e.to_string())).into_response()
+        })?;
+
+    state.pending_registrations.lock().insert(
+        user,
+        PendingRegistration {
+            metadata: UserMetadata {
+                is_email: None,
+                description: None,
+                nostr_pubkey: None,
+                comment_allowed: None,
+                nostr_relays: None,
+                route_hint_channels: None,
+            },
+            label,
+            bolt11: cln_response.bolt11.clone(),
+            expires_at,
+        },
+    );
+    save_pending_registrations(
+        &state.plugin_dir,
+        state.pending_registrations.lock().clone(),
+    )
+    .await
+    .map_err(|e| {
+        (StatusCode::INTERNAL_SERVER_ERROR, registration_error(&e.to_string())).into_response()
+    })?;
+
+    Ok(Json(RegisterResponse {
+        pr: cln_response.bolt11,
+        expires_at,
+    }))
+}
+
+/// `GET /register/{user}/confirm`. Meant to be polled by the registering
+/// client until the invoice from [`register_user`] settles.
+pub async fn registration_status(
+    Path(user): Path<String>,
+    State(state): State<PluginState>,
+) -> Result<Json<serde_json::Value>, axum::response::Response> {
+    if state
+        .users
+        .lookup(&user)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                registration_error(&e.to_string()),
+            )
+                .into_response()
+        })?
+        .is_some()
+    {
+        return Ok(Json(json!({"status": "confirmed"})));
+    }
+
+    let pending_expired = match state.pending_registrations.lock().get(&user) {
+        Some(pending) => pending.expires_at <= now_unix(),
+        None => {
+            return Err(
+                (StatusCode::NOT_FOUND, registration_error("No such registration"))
+                    .into_response(),
+            )
+        }
+    };
+
+    if pending_expired {
+        state.pending_registrations.lock().remove(&user);
+        return Ok(Json(json!({"status": "expired"})));
+    }
+
+    Ok(Json(json!({"status": "pending"})))
+}
+
+/// Promotes `user`'s pending registration into the live user store once its
+/// invoice has settled. A no-op if there's no matching pending entry, e.g.
+/// the invoice expired and was evicted, or was already promoted.
+pub async fn promote_registration(state: &PluginState, user: &str) -> Result<(), anyhow::Error> {
+    let Some(pending) = state.pending_registrations.lock().remove(user) else {
+        return Ok(());
+    };
+    state
+        .users
+        .upsert(user.to_owned(), pending.metadata)
+        .await?;
+    save_pending_registrations(&state.plugin_dir, state.pending_registrations.lock().clone())
+        .await
+}
+
+fn registration_price(state: &PluginState) -> Result<Option<u64>, axum::response::Response> {
+    if !state.registration_enabled {
+        return Ok(None);
+    }
+    Ok(Some(state.registration_price_msat.ok_or_else(|| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            registration_error("Registration is enabled but has no price configured"),
+        )
+            .into_response()
+    })?))
+}
+
+fn valid_user_name(user: &str) -> bool {
+    !user.is_empty()
+        && user.len() <= 64
+        && user
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+fn now_unix() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn registration_error(error: &str) -> Json<serde_json::Value> {
+    log::debug!("registration_error: {error}");
+    Json(json!({"status": "ERROR", "reason": error}))
+}
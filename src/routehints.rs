@@ -0,0 +1,55 @@
+use std::collections::HashSet;
+
+use cln_rpc::{model::requests::ListpeerchannelsRequest, primitives::ShortChannelId, ClnRpc};
+
+use crate::structs::PluginState;
+
+/// Hard cap on how many private-channel route hints get embedded in a
+/// single invoice, so the resulting QR code stays a reasonable size.
+const MAX_ROUTE_HINTS: usize = 4;
+
+/// Picks private channels worth hinting at for an invoice of `amount_msat`:
+/// a channel needs enough inbound (`receivable`) capacity to actually carry
+/// the payment, and if `allowed` is set (a per-user restriction configured
+/// via `clnaddress-adduser`) its short channel id or peer id must appear in
+/// it. Returns `None` when route hints are disabled or nothing qualifies,
+/// leaving `InvoiceRequest::exposeprivatechannels` untouched so default
+/// behavior doesn't change.
+pub async fn route_hint_channels(
+    rpc: &mut ClnRpc,
+    state: &PluginState,
+    allowed: Option<&[String]>,
+    amount_msat: u64,
+) -> Result<Option<Vec<ShortChannelId>>, anyhow::Error> {
+    if !state.expose_private_channels {
+        return Ok(None);
+    }
+
+    let allowed: Option<HashSet<&str>> = allowed.map(|a| a.iter().map(String::as_str).collect());
+
+    let peer_channels = rpc.call_typed(&ListpeerchannelsRequest { id: None }).await?;
+
+    let hints: Vec<ShortChannelId> = peer_channels
+        .channels
+        .into_iter()
+        .filter(|c| c.private.unwrap_or(false))
+        .filter(|c| {
+            c.receivable_msat
+                .map_or(false, |a| a.msat() >= amount_msat)
+        })
+        .filter_map(|c| {
+            let scid = c.short_channel_id?;
+            let eligible = match &allowed {
+                Some(allowed) => {
+                    allowed.contains(scid.to_string().as_str())
+                        || allowed.contains(c.peer_id.to_string().as_str())
+                }
+                None => true,
+            };
+            eligible.then_some(scid)
+        })
+        .take(MAX_ROUTE_HINTS)
+        .collect();
+
+    Ok((!hints.is_empty()).then_some(hints))
+}
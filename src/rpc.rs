@@ -1,120 +1,237 @@
-use std::{collections::HashMap, path::Path};
-
 use anyhow::anyhow;
 use cln_plugin::Plugin;
 use serde_json::json;
-use tokio::fs;
 
-use crate::{structs::UserMetadata, PluginState, CLNADDRESS_USERS_FILENAME};
+use crate::{store::UserStore, structs::UserMetadata, PluginState};
+
+/// Accepts a hex or `npub` encoded key and normalizes it to hex.
+fn parse_nostr_pubkey(value: &serde_json::Value) -> Result<Option<String>, anyhow::Error> {
+    let Some(s) = value.as_str() else {
+        return Err(anyhow!("`nostr_pubkey` has invalid type"));
+    };
+    Ok(Some(
+        nostr_sdk::PublicKey::parse(s)
+            .map_err(|e| anyhow!("`nostr_pubkey` is invalid: {e}"))?
+            .to_hex(),
+    ))
+}
+
+/// Accepts a comma-separated string or a JSON array of relay URLs,
+/// published alongside the user's NIP-05 pubkey.
+fn parse_nostr_relays(value: &serde_json::Value) -> Result<Option<Vec<String>>, anyhow::Error> {
+    match value {
+        serde_json::Value::String(s) => {
+            Ok(Some(s.split(',').map(|r| r.trim().to_owned()).collect()))
+        }
+        serde_json::Value::Array(values) => Ok(Some(
+            values
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .map(ToOwned::to_owned)
+                        .ok_or_else(|| anyhow!("`nostr_relays` element has invalid type"))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        )),
+        _ => Err(anyhow!("`nostr_relays` has invalid type")),
+    }
+}
+
+/// Accepts a comma-separated string or a JSON array of short channel ids or
+/// peer ids, restricting which private channels
+/// `clnaddress-expose-private-channels` may hint at for this user.
+fn parse_route_hint_channels(
+    value: &serde_json::Value,
+) -> Result<Option<Vec<String>>, anyhow::Error> {
+    match value {
+        serde_json::Value::String(s) => {
+            Ok(Some(s.split(',').map(|r| r.trim().to_owned()).collect()))
+        }
+        serde_json::Value::Array(values) => Ok(Some(
+            values
+                .iter()
+                .map(|v| {
+                    v.as_str()
+                        .map(ToOwned::to_owned)
+                        .ok_or_else(|| anyhow!("`route_hint_channels` element has invalid type"))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        )),
+        _ => Err(anyhow!("`route_hint_channels` has invalid type")),
+    }
+}
 
 pub async fn user_add(
     plugin: Plugin<PluginState>,
     args: serde_json::Value,
 ) -> Result<serde_json::Value, anyhow::Error> {
-    let result;
-    let user;
-    let metadata;
-    let users_clone;
-    {
-        let mut users = plugin.state().users.lock();
-        (user, metadata) = match args {
-            serde_json::Value::String(s) => (
-                s,
-                UserMetadata {
-                    is_email: None,
-                    description: None,
-                },
-            ),
-            serde_json::Value::Array(values) => {
-                let is_email_val = values.get(1);
-                log::debug!("{:?}", is_email_val);
-                let is_email = if let Some(val) = is_email_val {
-                    match val {
-                        serde_json::Value::Bool(b) => Some(*b),
-                        serde_json::Value::String(s) => Some(s.parse()?),
-                        _ => return Err(anyhow!("`is_email` has invalid type")),
-                    }
-                } else {
-                    None
-                };
-                let description_val = values.get(2);
-                let description = if let Some(desc) = description_val {
-                    match desc {
-                        serde_json::Value::Number(number) => Some(number.to_string()),
-                        serde_json::Value::String(s) => Some(s.to_owned()),
-                        _ => return Err(anyhow!("`description` has invalid type")),
+    let (user, metadata) = match args {
+        serde_json::Value::String(s) => (
+            s,
+            UserMetadata {
+                is_email: None,
+                description: None,
+                nostr_pubkey: None,
+                comment_allowed: None,
+                nostr_relays: None,
+                route_hint_channels: None,
+            },
+        ),
+        serde_json::Value::Array(values) => {
+            let is_email_val = values.get(1);
+            log::debug!("{:?}", is_email_val);
+            let is_email = if let Some(val) = is_email_val {
+                match val {
+                    serde_json::Value::Bool(b) => Some(*b),
+                    serde_json::Value::String(s) => Some(s.parse()?),
+                    _ => return Err(anyhow!("`is_email` has invalid type")),
+                }
+            } else {
+                None
+            };
+            let description_val = values.get(2);
+            let description = if let Some(desc) = description_val {
+                match desc {
+                    serde_json::Value::Number(number) => Some(number.to_string()),
+                    serde_json::Value::String(s) => Some(s.to_owned()),
+                    _ => return Err(anyhow!("`description` has invalid type")),
+                }
+            } else {
+                None
+            };
+            let nostr_pubkey = match values.get(3) {
+                Some(val) => parse_nostr_pubkey(val)?,
+                None => None,
+            };
+            let comment_allowed_val = values.get(4);
+            let comment_allowed = if let Some(val) = comment_allowed_val {
+                match val {
+                    serde_json::Value::Number(number) => {
+                        Some(number.as_u64().ok_or_else(|| {
+                            anyhow!("`comment_allowed` must be a positive integer")
+                        })?)
                     }
-                } else {
-                    None
-                };
+                    serde_json::Value::String(s) => Some(s.parse()?),
+                    _ => return Err(anyhow!("`comment_allowed` has invalid type")),
+                }
+            } else {
+                None
+            };
+            let nostr_relays = match values.get(5) {
+                Some(val) => parse_nostr_relays(val)?,
+                None => None,
+            };
+            let route_hint_channels = match values.get(6) {
+                Some(val) => parse_route_hint_channels(val)?,
+                None => None,
+            };
 
-                let user_val = values.first().ok_or_else(|| anyhow!("Empty array input"))?;
-                let user_string = match user_val {
-                    serde_json::Value::Number(number) => number.to_string(),
-                    serde_json::Value::String(s) => s.to_owned(),
-                    _ => return Err(anyhow!("Array user element has invalid type")),
-                };
+            let user_val = values.first().ok_or_else(|| anyhow!("Empty array input"))?;
+            let user_string = match user_val {
+                serde_json::Value::Number(number) => number.to_string(),
+                serde_json::Value::String(s) => s.to_owned(),
+                _ => return Err(anyhow!("Array user element has invalid type")),
+            };
 
-                (
-                    user_string,
-                    UserMetadata {
-                        is_email,
-                        description,
-                    },
-                )
-            }
-            serde_json::Value::Object(map) => {
-                let is_email_val = map.get("is_email");
-                let is_email = if let Some(val) = is_email_val {
-                    match val {
-                        serde_json::Value::Bool(b) => Some(*b),
-                        serde_json::Value::String(s) => Some(s.parse()?),
-                        _ => return Err(anyhow!("`is_email` has invalid type")),
-                    }
-                } else {
-                    None
-                };
-                let description_val = map.get("description");
-                let description = if let Some(desc) = description_val {
-                    match desc {
-                        serde_json::Value::Number(number) => Some(number.to_string()),
-                        serde_json::Value::String(s) => Some(s.to_owned()),
-                        _ => return Err(anyhow!("`description` has invalid type")),
+            (
+                user_string,
+                UserMetadata {
+                    is_email,
+                    description,
+                    nostr_pubkey,
+                    comment_allowed,
+                    nostr_relays,
+                    route_hint_channels,
+                },
+            )
+        }
+        serde_json::Value::Object(map) => {
+            let is_email_val = map.get("is_email");
+            let is_email = if let Some(val) = is_email_val {
+                match val {
+                    serde_json::Value::Bool(b) => Some(*b),
+                    serde_json::Value::String(s) => Some(s.parse()?),
+                    _ => return Err(anyhow!("`is_email` has invalid type")),
+                }
+            } else {
+                None
+            };
+            let description_val = map.get("description");
+            let description = if let Some(desc) = description_val {
+                match desc {
+                    serde_json::Value::Number(number) => Some(number.to_string()),
+                    serde_json::Value::String(s) => Some(s.to_owned()),
+                    _ => return Err(anyhow!("`description` has invalid type")),
+                }
+            } else {
+                None
+            };
+            let nostr_pubkey = match map.get("nostr_pubkey") {
+                Some(val) => parse_nostr_pubkey(val)?,
+                None => None,
+            };
+            let comment_allowed_val = map.get("comment_allowed");
+            let comment_allowed = if let Some(val) = comment_allowed_val {
+                match val {
+                    serde_json::Value::Number(number) => {
+                        Some(number.as_u64().ok_or_else(|| {
+                            anyhow!("`comment_allowed` must be a positive integer")
+                        })?)
                     }
-                } else {
-                    None
-                };
+                    serde_json::Value::String(s) => Some(s.parse()?),
+                    _ => return Err(anyhow!("`comment_allowed` has invalid type")),
+                }
+            } else {
+                None
+            };
+            let nostr_relays = match map.get("nostr_relays") {
+                Some(val) => parse_nostr_relays(val)?,
+                None => None,
+            };
+            let route_hint_channels = match map.get("route_hint_channels") {
+                Some(val) => parse_route_hint_channels(val)?,
+                None => None,
+            };
 
-                let user_val = map
-                    .get("user")
-                    .ok_or_else(|| anyhow!("`user` field not found in object"))?;
-                let user_string = match user_val {
-                    serde_json::Value::Number(number) => number.to_string(),
-                    serde_json::Value::String(s) => s.to_owned(),
-                    _ => return Err(anyhow!("user field has invalid type")),
-                };
+            let user_val = map
+                .get("user")
+                .ok_or_else(|| anyhow!("`user` field not found in object"))?;
+            let user_string = match user_val {
+                serde_json::Value::Number(number) => number.to_string(),
+                serde_json::Value::String(s) => s.to_owned(),
+                _ => return Err(anyhow!("user field has invalid type")),
+            };
 
-                (
-                    user_string,
-                    UserMetadata {
-                        is_email,
-                        description,
-                    },
-                )
-            }
-            serde_json::Value::Number(n) => (
-                n.to_string(),
+            (
+                user_string,
                 UserMetadata {
-                    is_email: None,
-                    description: None,
+                    is_email,
+                    description,
+                    nostr_pubkey,
+                    comment_allowed,
+                    nostr_relays,
+                    route_hint_channels,
                 },
-            ),
-            _ => return Err(anyhow!("Not a valid input type")),
-        };
-        result = users.insert(user.clone(), metadata.clone());
-        users_clone = users.clone();
-    }
-    save_users(&plugin.state().plugin_dir, users_clone).await?;
+            )
+        }
+        serde_json::Value::Number(n) => (
+            n.to_string(),
+            UserMetadata {
+                is_email: None,
+                description: None,
+                nostr_pubkey: None,
+                comment_allowed: None,
+                nostr_relays: None,
+                route_hint_channels: None,
+            },
+        ),
+        _ => return Err(anyhow!("Not a valid input type")),
+    };
+    let result = plugin
+        .state()
+        .users
+        .upsert(user.clone(), metadata.clone())
+        .await?;
     let mut mode = if let Some(_res) = result {
         json!({"mode":"updated"})
     } else {
@@ -134,33 +251,25 @@ pub async fn user_del(
     plugin: Plugin<PluginState>,
     args: serde_json::Value,
 ) -> Result<serde_json::Value, anyhow::Error> {
-    let result;
-    let user;
-    let users_clone;
-    {
-        let mut users = plugin.state().users.lock();
-        user = match args {
-            serde_json::Value::String(s) => s,
-            serde_json::Value::Array(values) => values
-                .first()
-                .ok_or_else(|| anyhow!("Empty array input"))?
-                .as_str()
-                .ok_or_else(|| anyhow!("Array elemnt not a string"))?
-                .to_owned(),
-            serde_json::Value::Object(map) => map
-                .get("user")
-                .ok_or_else(|| anyhow!("`user` element not found in object"))?
-                .as_str()
-                .ok_or_else(|| anyhow!("Array elemnt not a string"))?
-                .to_owned(),
-            serde_json::Value::Number(n) => n.to_string(),
-            _ => return Err(anyhow!("Not a valid input type")),
-        };
-        result = users.remove(&user);
-        users_clone = users.clone();
-    }
+    let user = match args {
+        serde_json::Value::String(s) => s,
+        serde_json::Value::Array(values) => values
+            .first()
+            .ok_or_else(|| anyhow!("Empty array input"))?
+            .as_str()
+            .ok_or_else(|| anyhow!("Array elemnt not a string"))?
+            .to_owned(),
+        serde_json::Value::Object(map) => map
+            .get("user")
+            .ok_or_else(|| anyhow!("`user` element not found in object"))?
+            .as_str()
+            .ok_or_else(|| anyhow!("Array elemnt not a string"))?
+            .to_owned(),
+        serde_json::Value::Number(n) => n.to_string(),
+        _ => return Err(anyhow!("Not a valid input type")),
+    };
+    let result = plugin.state().users.delete(&user).await?;
     if let Some(res) = result {
-        save_users(&plugin.state().plugin_dir, users_clone).await?;
         let mut mode = json!({"mode":"deleted"});
 
         mode.as_object_mut()
@@ -176,11 +285,47 @@ pub async fn user_del(
     }
 }
 
-pub async fn save_users(
-    path: &Path,
-    users: HashMap<String, UserMetadata>,
-) -> Result<(), anyhow::Error> {
-    let serialized = serde_json::to_string(&users)?;
-    fs::write(path.join(CLNADDRESS_USERS_FILENAME), serialized).await?;
-    Ok(())
+pub async fn user_list(
+    plugin: Plugin<PluginState>,
+    args: serde_json::Value,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let user = match args {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) => (!s.is_empty()).then_some(s),
+        serde_json::Value::Array(values) => match values.first() {
+            Some(serde_json::Value::String(s)) => Some(s.to_owned()),
+            Some(serde_json::Value::Number(n)) => Some(n.to_string()),
+            None => None,
+            _ => return Err(anyhow!("Array elemnt not a string")),
+        },
+        serde_json::Value::Object(map) => match map.get("user") {
+            Some(serde_json::Value::String(s)) => Some(s.to_owned()),
+            Some(serde_json::Value::Number(n)) => Some(n.to_string()),
+            None => None,
+            _ => return Err(anyhow!("`user` field has invalid type")),
+        },
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        _ => return Err(anyhow!("Not a valid input type")),
+    };
+
+    match user {
+        Some(user) => {
+            let metadata = plugin
+                .state()
+                .users
+                .lookup(&user)
+                .await?
+                .ok_or_else(|| anyhow!("User not found"))?;
+            let mut result = json!({"user": user});
+            result
+                .as_object_mut()
+                .unwrap()
+                .extend(json!(metadata).as_object().unwrap().clone());
+            Ok(result)
+        }
+        None => {
+            let users = plugin.state().users.list().await?;
+            Ok(json!({ "users": users }))
+        }
+    }
 }
@@ -0,0 +1,109 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use tokio::fs;
+
+use crate::{structs::UserMetadata, CLNADDRESS_USERS_FILENAME};
+
+#[cfg(feature = "ldap")]
+mod ldap;
+#[cfg(feature = "ldap")]
+pub use ldap::{LdapConfig, LdapUserStore};
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteUserStore;
+
+/// Abstracts over where `user` -> [`UserMetadata`] mappings come from, so
+/// addresses can be backed by the bundled JSON file or by an external
+/// directory service.
+#[async_trait]
+pub trait UserStore: Send + Sync + std::fmt::Debug {
+    /// Called once at startup, e.g. to load the JSON file from disk.
+    async fn init(&self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+    async fn lookup(&self, user: &str) -> Result<Option<UserMetadata>, anyhow::Error>;
+    async fn list(&self) -> Result<HashMap<String, UserMetadata>, anyhow::Error>;
+    async fn upsert(
+        &self,
+        user: String,
+        metadata: UserMetadata,
+    ) -> Result<Option<UserMetadata>, anyhow::Error>;
+    async fn delete(&self, user: &str) -> Result<Option<UserMetadata>, anyhow::Error>;
+}
+
+/// The default backend: one JSON file holding all users, mirroring the
+/// behavior this plugin has always had.
+#[derive(Debug, Clone)]
+pub struct JsonUserStore {
+    plugin_dir: PathBuf,
+    users: std::sync::Arc<Mutex<HashMap<String, UserMetadata>>>,
+}
+
+impl JsonUserStore {
+    pub fn new(plugin_dir: PathBuf) -> Self {
+        Self {
+            plugin_dir,
+            users: std::sync::Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    async fn save(&self) -> Result<(), anyhow::Error> {
+        let serialized = serde_json::to_string(&self.users.lock().clone())?;
+        // Write to a temp file first and rename over the target so a crash
+        // mid-write can't truncate or corrupt the existing users file.
+        let tmp_path = self
+            .plugin_dir
+            .join(format!("{CLNADDRESS_USERS_FILENAME}.tmp"));
+        fs::write(&tmp_path, serialized).await?;
+        fs::rename(&tmp_path, self.plugin_dir.join(CLNADDRESS_USERS_FILENAME)).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserStore for JsonUserStore {
+    async fn init(&self) -> Result<(), anyhow::Error> {
+        match fs::read_to_string(self.plugin_dir.join(CLNADDRESS_USERS_FILENAME)).await {
+            Ok(content) => *self.users.lock() = serde_json::from_str(&content)?,
+            Err(e) => match e.kind() {
+                std::io::ErrorKind::NotFound => (),
+                _ => {
+                    return Err(anyhow!(
+                        "Could not read {CLNADDRESS_USERS_FILENAME} file: {e}"
+                    ))
+                }
+            },
+        }
+        Ok(())
+    }
+
+    async fn lookup(&self, user: &str) -> Result<Option<UserMetadata>, anyhow::Error> {
+        Ok(self.users.lock().get(user).cloned())
+    }
+
+    async fn list(&self) -> Result<HashMap<String, UserMetadata>, anyhow::Error> {
+        Ok(self.users.lock().clone())
+    }
+
+    async fn upsert(
+        &self,
+        user: String,
+        metadata: UserMetadata,
+    ) -> Result<Option<UserMetadata>, anyhow::Error> {
+        let result = self.users.lock().insert(user, metadata);
+        self.save().await?;
+        Ok(result)
+    }
+
+    async fn delete(&self, user: &str) -> Result<Option<UserMetadata>, anyhow::Error> {
+        let result = self.users.lock().remove(user);
+        if result.is_some() {
+            self.save().await?;
+        }
+        Ok(result)
+    }
+}
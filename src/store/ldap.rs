@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use ldap3::{LdapConnAsync, Scope, SearchEntry};
+
+use super::UserStore;
+use crate::structs::UserMetadata;
+
+/// Connection and attribute-mapping settings for the LDAP-backed
+/// [`UserStore`], built from the `clnaddress-ldap-*` options.
+#[derive(Debug, Clone)]
+pub struct LdapConfig {
+    pub url: String,
+    pub bind_dn: String,
+    pub bind_password: String,
+    pub search_base: String,
+    /// `{user}` is replaced with the requested address local-part.
+    pub search_filter: String,
+    pub attr_description: Option<String>,
+    pub attr_is_email: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LdapUserStore {
+    config: LdapConfig,
+}
+
+/// Escapes a filter value per RFC 4515 so it can't break out of the
+/// configured `search_filter` (LDAP injection via `*`, `(`, `)`, `\` or a
+/// NUL byte).
+fn escape_filter_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '*' => escaped.push_str("\\2a"),
+            '(' => escaped.push_str("\\28"),
+            ')' => escaped.push_str("\\29"),
+            '\\' => escaped.push_str("\\5c"),
+            '\0' => escaped.push_str("\\00"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl LdapUserStore {
+    pub fn new(config: LdapConfig) -> Self {
+        Self { config }
+    }
+
+    async fn connect(&self) -> Result<ldap3::Ldap, anyhow::Error> {
+        let (conn, mut ldap) = LdapConnAsync::new(&self.config.url).await?;
+        ldap3::drive!(conn);
+        ldap.simple_bind(&self.config.bind_dn, &self.config.bind_password)
+            .await?
+            .success()?;
+        Ok(ldap)
+    }
+
+    fn entry_to_metadata(&self, entry: SearchEntry) -> UserMetadata {
+        let description = self
+            .config
+            .attr_description
+            .as_ref()
+            .and_then(|attr| entry.attrs.get(attr))
+            .and_then(|values| values.first())
+            .cloned();
+        let is_email = self
+            .config
+            .attr_is_email
+            .as_ref()
+            .and_then(|attr| entry.attrs.get(attr))
+            .and_then(|values| values.first())
+            .and_then(|v| v.parse::<bool>().ok());
+
+        UserMetadata {
+            is_email,
+            description,
+            nostr_pubkey: None,
+            comment_allowed: None,
+            nostr_relays: None,
+            route_hint_channels: None,
+        }
+    }
+}
+
+#[async_trait]
+impl UserStore for LdapUserStore {
+    async fn lookup(&self, user: &str) -> Result<Option<UserMetadata>, anyhow::Error> {
+        let mut ldap = self.connect().await?;
+        let filter = self
+            .config
+            .search_filter
+            .replace("{user}", &escape_filter_value(user));
+        let (entries, _res) = ldap
+            .search(&self.config.search_base, Scope::Subtree, &filter, vec![
+                "*",
+            ])
+            .await?
+            .success()?;
+        let Some(raw_entry) = entries.into_iter().next() else {
+            return Ok(None);
+        };
+        Ok(Some(self.entry_to_metadata(SearchEntry::construct(raw_entry))))
+    }
+
+    async fn list(&self) -> Result<HashMap<String, UserMetadata>, anyhow::Error> {
+        Err(anyhow!(
+            "Listing all users is not supported by the LDAP backend"
+        ))
+    }
+
+    async fn upsert(
+        &self,
+        _user: String,
+        _metadata: UserMetadata,
+    ) -> Result<Option<UserMetadata>, anyhow::Error> {
+        Err(anyhow!(
+            "Adding users is not supported by the LDAP backend, provision them in the directory"
+        ))
+    }
+
+    async fn delete(&self, _user: &str) -> Result<Option<UserMetadata>, anyhow::Error> {
+        Err(anyhow!(
+            "Removing users is not supported by the LDAP backend, provision them in the directory"
+        ))
+    }
+}
@@ -0,0 +1,163 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::UserStore;
+use crate::structs::UserMetadata;
+
+/// Row-per-user storage for deployments that don't want to rewrite the whole
+/// user list on every mutation, unlike [`super::JsonUserStore`].
+#[derive(Debug, Clone)]
+pub struct SqliteUserStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteUserStore {
+    pub fn new(plugin_dir: PathBuf) -> Result<Self, anyhow::Error> {
+        let conn = Connection::open(plugin_dir.join("users.sqlite3"))?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS users (
+                user TEXT PRIMARY KEY,
+                is_email INTEGER,
+                description TEXT,
+                nostr_pubkey TEXT,
+                comment_allowed INTEGER,
+                nostr_relays TEXT,
+                route_hint_channels TEXT
+            )",
+            [],
+        )?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn row_to_metadata(row: &rusqlite::Row) -> rusqlite::Result<UserMetadata> {
+        Ok(UserMetadata {
+            is_email: row.get::<_, Option<bool>>(1)?,
+            description: row.get(2)?,
+            nostr_pubkey: row.get(3)?,
+            comment_allowed: row.get::<_, Option<i64>>(4)?.map(|n| n as u64),
+            nostr_relays: row
+                .get::<_, Option<String>>(5)?
+                .and_then(|s| serde_json::from_str(&s).ok()),
+            route_hint_channels: row
+                .get::<_, Option<String>>(6)?
+                .and_then(|s| serde_json::from_str(&s).ok()),
+        })
+    }
+}
+
+#[async_trait]
+impl UserStore for SqliteUserStore {
+    async fn lookup(&self, user: &str) -> Result<Option<UserMetadata>, anyhow::Error> {
+        let conn = self.conn.clone();
+        let user = user.to_owned();
+        tokio::task::spawn_blocking(move || {
+            conn.lock()
+                .query_row(
+                    "SELECT user, is_email, description, nostr_pubkey, comment_allowed, nostr_relays, \
+                     route_hint_channels \
+                 FROM users WHERE user = ?1",
+                    params![user],
+                    Self::row_to_metadata,
+                )
+                .optional()
+                .map_err(|e| anyhow!("Could not look up user in sqlite database: {e}"))
+        })
+        .await?
+    }
+
+    async fn list(&self) -> Result<HashMap<String, UserMetadata>, anyhow::Error> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock();
+            let mut stmt = conn.prepare(
+                "SELECT user, is_email, description, nostr_pubkey, comment_allowed, nostr_relays, \
+                     route_hint_channels \
+                 FROM users",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, Self::row_to_metadata(row)?))
+            })?;
+            rows.collect::<rusqlite::Result<HashMap<_, _>>>()
+                .map_err(|e| anyhow!("Could not list users in sqlite database: {e}"))
+        })
+        .await?
+    }
+
+    async fn upsert(
+        &self,
+        user: String,
+        metadata: UserMetadata,
+    ) -> Result<Option<UserMetadata>, anyhow::Error> {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock();
+            let previous = conn
+                .query_row(
+                    "SELECT user, is_email, description, nostr_pubkey, comment_allowed, nostr_relays, \
+                     route_hint_channels \
+                 FROM users WHERE user = ?1",
+                    params![user],
+                    Self::row_to_metadata,
+                )
+                .optional()?;
+            conn.execute(
+                "INSERT INTO users (user, is_email, description, nostr_pubkey, comment_allowed, nostr_relays, route_hint_channels)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                 ON CONFLICT(user) DO UPDATE SET
+                    is_email = excluded.is_email,
+                    description = excluded.description,
+                    nostr_pubkey = excluded.nostr_pubkey,
+                    comment_allowed = excluded.comment_allowed,
+                    nostr_relays = excluded.nostr_relays,
+                    route_hint_channels = excluded.route_hint_channels",
+                params![
+                    user,
+                    metadata.is_email,
+                    metadata.description,
+                    metadata.nostr_pubkey,
+                    metadata.comment_allowed.map(|n| n as i64),
+                    metadata
+                        .nostr_relays
+                        .as_ref()
+                        .map(|relays| serde_json::to_string(relays).unwrap()),
+                    metadata
+                        .route_hint_channels
+                        .as_ref()
+                        .map(|channels| serde_json::to_string(channels).unwrap()),
+                ],
+            )?;
+            Ok::<_, rusqlite::Error>(previous)
+        })
+        .await?
+        .map_err(|e| anyhow!("Could not upsert user in sqlite database: {e}"))
+    }
+
+    async fn delete(&self, user: &str) -> Result<Option<UserMetadata>, anyhow::Error> {
+        let conn = self.conn.clone();
+        let user = user.to_owned();
+        tokio::task::spawn_blocking(move || {
+            let conn = conn.lock();
+            let previous = conn
+                .query_row(
+                    "SELECT user, is_email, description, nostr_pubkey, comment_allowed, nostr_relays, \
+                     route_hint_channels \
+                 FROM users WHERE user = ?1",
+                    params![user],
+                    Self::row_to_metadata,
+                )
+                .optional()?;
+            if previous.is_some() {
+                conn.execute("DELETE FROM users WHERE user = ?1", params![user])?;
+            }
+            Ok::<_, rusqlite::Error>(previous)
+        })
+        .await?
+        .map_err(|e| anyhow!("Could not delete user in sqlite database: {e}"))
+    }
+}
@@ -1,21 +1,78 @@
-use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
+use crate::{ratelimit::RateBucket, store::UserStore};
+
 #[derive(Debug, Clone)]
 pub struct PluginState {
     pub rpc_path: PathBuf,
     pub max_sendable_msat: u64,
     pub min_sendable_msat: u64,
     pub default_description: String,
-    pub users: Arc<Mutex<HashMap<String, UserMetadata>>>,
+    pub users: Arc<dyn UserStore>,
     pub plugin_dir: PathBuf,
     pub base_url: Url,
     pub nostr_zapper_keys: Option<nostr_sdk::key::Keys>,
     pub payindex: u64,
     pub listen_address: SocketAddr,
+    pub zap_requests: Arc<Mutex<HashMap<String, ZapRequestEntry>>>,
+    /// `None` means any origin is allowed (the default).
+    pub cors_allowed_origins: Option<Vec<String>>,
+    pub comment_allowed: u64,
+    /// URL notified with a JSON payload whenever an invoice gets paid.
+    pub webhook_url: Option<String>,
+    /// Shell command run whenever an invoice gets paid, fields passed as
+    /// `CLNADDRESS_*` environment variables.
+    pub exec_command: Option<String>,
+    /// PEM certificate for the LNURL server. Set together with `tls_key` to
+    /// serve HTTPS directly instead of plain HTTP.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key matching `tls_cert`.
+    pub tls_key: Option<PathBuf>,
+    /// Requests allowed per `rate_window` per client. `None` disables
+    /// rate limiting entirely.
+    pub rate_limit: Option<u64>,
+    pub rate_window: Duration,
+    /// Whether to key rate-limit buckets on the first address in a
+    /// client-supplied `X-Forwarded-For` header instead of the socket's
+    /// peer address. Only safe behind a trusted reverse proxy.
+    pub rate_trust_forwarded_for: bool,
+    pub rate_buckets: Arc<Mutex<HashMap<String, RateBucket>>>,
+    /// Whether `/register/{user}` is reachable at all.
+    pub registration_enabled: bool,
+    /// Price of a self-service registration invoice. `Some` whenever
+    /// `registration_enabled` is `true`.
+    pub registration_price_msat: Option<u64>,
+    /// How long an unpaid registration invoice stays valid before its
+    /// pending entry is evicted and the name becomes available again.
+    pub registration_expiry: Duration,
+    pub pending_registrations: Arc<Mutex<HashMap<String, PendingRegistration>>>,
+    /// Whether generated invoices may carry route hints for the payee's
+    /// private channels with enough inbound capacity for the requested
+    /// amount.
+    pub expose_private_channels: bool,
+}
+
+/// A pending zap request, keyed by the invoice `label` it was created for,
+/// kept around until `waitanyinvoice` reports the invoice as settled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZapRequestEntry {
+    pub zap_request: String,
+    pub relays: Vec<String>,
+}
+
+/// A name reserved behind an unpaid registration invoice, kept around
+/// until the invoice is paid (promoting it into the live user store) or it
+/// expires (freeing the name back up).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingRegistration {
+    pub metadata: UserMetadata,
+    pub label: String,
+    pub bolt11: String,
+    pub expires_at: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +81,24 @@ pub struct UserMetadata {
     pub is_email: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    /// Hex-encoded Nostr pubkey, normalized on insert and published in this
+    /// user's NIP-05 document. Does *not* affect zap receipt identity: NIP-57
+    /// receipts are always signed with the single node-wide
+    /// `clnaddress-nostr-privkey`, so `nostrPubkey` in the LNURL-pay config
+    /// also always reports that same global key rather than this one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nostr_pubkey: Option<String>,
+    /// Per-user override for the global `clnaddress-comment-allowed` option.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment_allowed: Option<u64>,
+    /// Relays published alongside `nostr_pubkey` in the NIP-05 document.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nostr_relays: Option<Vec<String>>,
+    /// Restricts which private channels (by short channel id or peer id)
+    /// `clnaddress-expose-private-channels` may hint at for this user's
+    /// invoices. `None` considers every eligible private channel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route_hint_channels: Option<Vec<String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -49,6 +124,10 @@ pub struct LnurlpConfig {
 pub struct InvoiceQueryParams {
     pub amount: u64,
     pub nostr: Option<String>,
+    /// LUD-12 comment.
+    pub comment: Option<String>,
+    /// LUD-18 payer data, raw JSON as sent by the wallet.
+    pub payerdata: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
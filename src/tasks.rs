@@ -1,20 +1,31 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use cln_plugin::Plugin;
 use cln_rpc::{model::requests::WaitanyinvoiceRequest, ClnRpc};
 use nostr_sdk::{
-    event::{Event, EventBuilder, TagKind},
+    event::{Event, EventBuilder},
     types::Timestamp,
     util::JsonUtil,
     Client,
 };
 use tokio::fs;
 
-use crate::{structs::PluginState, CLNADDRESS_PAYINDEX_FILENAME};
+use crate::{
+    lnurl::{description_comment, is_own_invoice_label, label_user},
+    notify::{dispatch, PaymentNotification},
+    registration::{promote_registration, registration_label_user},
+    structs::{PendingRegistration, PluginState, ZapRequestEntry},
+    CLNADDRESS_PAYINDEX_FILENAME,
+    CLNADDRESS_REGISTRATIONS_FILENAME,
+    CLNADDRESS_ZAPMAP_FILENAME,
+};
 
-pub async fn zap_receipt_sender(plugin: Plugin<PluginState>) -> Result<(), anyhow::Error> {
+/// Watches `waitanyinvoice` for settled invoices and, depending on what's
+/// configured, publishes a NIP-57 zap receipt and/or dispatches a payment
+/// notification for each one.
+pub async fn payment_watcher(plugin: Plugin<PluginState>) -> Result<(), anyhow::Error> {
     let mut rpc = ClnRpc::new(&plugin.state().rpc_path).await?;
-    let keys = plugin.state().nostr_zapper_keys.clone().unwrap();
+    let zapper_keys = plugin.state().nostr_zapper_keys.clone();
     let mut lastpay_index = plugin.state().payindex;
     log::debug!("lastpay_index: {lastpay_index}");
     loop {
@@ -28,12 +39,57 @@ pub async fn zap_receipt_sender(plugin: Plugin<PluginState>) -> Result<(), anyho
             Ok(o) => {
                 log::debug!("{o:?}");
                 lastpay_index = o.pay_index.unwrap_or(lastpay_index + 1);
-                save_payindex(&plugin.state().plugin_dir, lastpay_index).await?;
-                if let Some(desc) = o.description {
-                    if let Ok(event) = Event::from_json(desc.as_bytes()) {
+
+                if (plugin.state().webhook_url.is_some() || plugin.state().exec_command.is_some())
+                    && is_own_invoice_label(&o.label)
+                {
+                    let notification = PaymentNotification {
+                        user: label_user(&o.label).map(ToOwned::to_owned),
+                        label: o.label.clone(),
+                        amount_msat: o.amount_received_msat.map_or(0, |a| a.msat()),
+                        payment_hash: o.payment_hash.to_string(),
+                        preimage: o
+                            .payment_preimage
+                            .clone()
+                            .map(|p| serde_json::to_string(&p).unwrap()),
+                        comment: o.description.as_deref().and_then(description_comment),
+                        paid_at: o.paid_at,
+                        bolt11: o.bolt11.clone(),
+                    };
+                    tokio::spawn(dispatch(plugin.state().clone(), notification));
+                }
+
+                if let Some(user) = registration_label_user(&o.label) {
+                    if let Err(e) = promote_registration(plugin.state(), user).await {
+                        log::warn!("Could not promote registration for `{user}`: {e}");
+                    }
+                } else {
+                    'zap: {
+                        let Some(keys) = zapper_keys.clone() else {
+                            break 'zap;
+                        };
+
+                        let entry = plugin.state().zap_requests.lock().remove(&o.label);
+                        let Some(entry) = entry else {
+                            // Not an invoice we created for a zap, nothing to publish.
+                            break 'zap;
+                        };
+                        save_zap_requests(
+                            &plugin.state().plugin_dir,
+                            plugin.state().zap_requests.lock().clone(),
+                        )
+                        .await?;
+
+                        let Ok(event) = Event::from_json(entry.zap_request.as_bytes()) else {
+                            log::warn!(
+                                "Stored zap request for label {} is not valid JSON",
+                                o.label
+                            );
+                            break 'zap;
+                        };
                         let Some(bolt11) = o.bolt11 else {
                             log::warn!("No bolt11 found for zap receipt!");
-                            continue;
+                            break 'zap;
                         };
                         let mut zap_receipt = EventBuilder::zap_receipt(
                             bolt11,
@@ -50,30 +106,34 @@ pub async fn zap_receipt_sender(plugin: Plugin<PluginState>) -> Result<(), anyho
                             Ok(o) => o,
                             Err(e) => {
                                 log::warn!("Could not sign zap receipt:{e}");
-                                continue;
+                                break 'zap;
                             }
                         };
                         log::debug!("{}", zap_receipt.as_json());
 
+                        if entry.relays.is_empty() {
+                            log::warn!("No relays included in zap request!");
+                            break 'zap;
+                        }
                         let client = Client::new(keys.clone());
-
-                        if let Some(relay_tag) =
-                            event.tags.iter().find(|t| t.kind() == TagKind::Relays)
-                        {
-                            for relay_url in relay_tag.as_slice().iter().skip(1) {
-                                if let Err(e) = client.add_relay(relay_url).await {
-                                    log::warn!("Could not add relay {relay_url} to client: {e}");
-                                };
-                            }
-                            client.connect().await;
-                            if let Err(e) = client.send_event(&zap_receipt).await {
-                                log::warn!("Could not send zap receipt: {e}");
+                        for relay_url in entry.relays.iter() {
+                            if let Err(e) = client.add_relay(relay_url).await {
+                                log::warn!("Could not add relay {relay_url} to client: {e}");
                             };
-                        } else {
-                            log::warn!("No relays included in zap request!");
                         }
+                        client.connect().await;
+                        if let Err(e) = client.send_event(&zap_receipt).await {
+                            log::warn!("Could not send zap receipt: {e}");
+                        };
                     }
                 }
+
+                // Only advance (and persist) the payindex once the webhook/
+                // exec dispatch has been enqueued and any registration
+                // promotion has been applied, so a crash can't lose either
+                // one: `waitanyinvoice` will simply replay this pay_index
+                // on the next startup instead.
+                save_payindex(&plugin.state().plugin_dir, lastpay_index).await?;
             }
             Err(e) => {
                 log::warn!("Err waiting on invoices: {e}");
@@ -87,3 +147,21 @@ pub async fn save_payindex(path: &Path, payindex: u64) -> Result<(), anyhow::Err
     fs::write(path.join(CLNADDRESS_PAYINDEX_FILENAME), serialized).await?;
     Ok(())
 }
+
+pub async fn save_zap_requests(
+    path: &Path,
+    zap_requests: HashMap<String, ZapRequestEntry>,
+) -> Result<(), anyhow::Error> {
+    let serialized = serde_json::to_string(&zap_requests)?;
+    fs::write(path.join(CLNADDRESS_ZAPMAP_FILENAME), serialized).await?;
+    Ok(())
+}
+
+pub async fn save_pending_registrations(
+    path: &Path,
+    pending_registrations: HashMap<String, PendingRegistration>,
+) -> Result<(), anyhow::Error> {
+    let serialized = serde_json::to_string(&pending_registrations)?;
+    fs::write(path.join(CLNADDRESS_REGISTRATIONS_FILENAME), serialized).await?;
+    Ok(())
+}
@@ -0,0 +1,28 @@
+use std::{path::PathBuf, time::Duration};
+
+use axum_server::tls_rustls::RustlsConfig;
+
+/// How often the cert/key files are re-read, so a renewed certificate takes
+/// effect without having to restart the plugin.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Loads a rustls server config from a PEM cert/key pair.
+pub async fn load_tls_config(cert: &PathBuf, key: &PathBuf) -> Result<RustlsConfig, anyhow::Error> {
+    RustlsConfig::from_pem_file(cert, key)
+        .await
+        .map_err(|e| anyhow::anyhow!("Could not load TLS cert/key: {e}"))
+}
+
+/// Re-reads `cert`/`key` into `config` on [`RELOAD_INTERVAL`], so renewed
+/// certificates get picked up without a plugin restart. Meant to be spawned
+/// as its own task alongside the server using `config`.
+pub async fn reload_tls_config(config: RustlsConfig, cert: PathBuf, key: PathBuf) {
+    let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(e) = config.reload_from_pem_file(&cert, &key).await {
+            log::warn!("Could not reload TLS cert/key: {e}");
+        }
+    }
+}